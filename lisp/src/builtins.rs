@@ -0,0 +1,126 @@
+//! `BuiltinFn` implementations for the standard procedures that don't
+//! have anywhere more specific to live yet.
+
+use cell_gc::GcHeapSession;
+use cell_gc::collections::MapRef;
+use value::{BuiltinFn, Pair, Value};
+use vm::Trampoline;
+
+fn ok<'h>(value: Value<'h>) -> Result<Trampoline<'h>, String> {
+    Ok(Trampoline::Value(value))
+}
+
+fn check_arity<'h>(name: &str, args: &[Value<'h>], expected: usize) -> Result<(), String> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(format!("{}: expected {} argument(s), got {}", name, expected, args.len()))
+    }
+}
+
+fn check_hashable_key<'h>(name: &str, key: &Value<'h>) -> Result<(), String> {
+    if key.is_hashable_key() {
+        Ok(())
+    } else {
+        Err(format!("{}: key must be a symbol, integer, boolean, or string", name))
+    }
+}
+
+pub fn make_hash_table<'h>(hs: &mut GcHeapSession<'h>, args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("make-hash-table", &args, 0)?;
+    let map: MapRef<'h, Value<'h>, Value<'h>> = MapRef::new(hs);
+    ok(Value::HashTable(map))
+}
+
+pub fn hash_table_set<'h>(_hs: &mut GcHeapSession<'h>, mut args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("hash-table-set!", &args, 3)?;
+    let value = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    check_hashable_key("hash-table-set!", &key)?;
+    let map = args.pop().unwrap().as_hash_table("hash-table-set!")?;
+    map.insert(key, value);
+    ok(Value::Bool(true))
+}
+
+pub fn hash_table_ref<'h>(_hs: &mut GcHeapSession<'h>, mut args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("hash-table-ref", &args, 2)?;
+    let key = args.pop().unwrap();
+    check_hashable_key("hash-table-ref", &key)?;
+    let map = args.pop().unwrap().as_hash_table("hash-table-ref")?;
+    ok(map.get(&key).unwrap_or(Value::Bool(false)))
+}
+
+pub fn hash_table_delete<'h>(_hs: &mut GcHeapSession<'h>, mut args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("hash-table-delete!", &args, 2)?;
+    let key = args.pop().unwrap();
+    check_hashable_key("hash-table-delete!", &key)?;
+    let map = args.pop().unwrap().as_hash_table("hash-table-delete!")?;
+    map.remove(&key);
+    ok(Value::Bool(true))
+}
+
+pub fn hash_table_count<'h>(_hs: &mut GcHeapSession<'h>, mut args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("hash-table-count", &args, 1)?;
+    let map = args.pop().unwrap().as_hash_table("hash-table-count")?;
+    ok(Value::Int(map.len() as i32))
+}
+
+pub fn hash_table_to_alist<'h>(hs: &mut GcHeapSession<'h>, mut args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("hash-table->alist", &args, 1)?;
+    let map = args.pop().unwrap().as_hash_table("hash-table->alist")?;
+    let mut result = Value::Nil;
+    for (key, value) in map.iter() {
+        let entry = hs.alloc(Pair { car: key, cdr: value });
+        result = Value::Cons(hs.alloc(Pair { car: Value::Cons(entry), cdr: result }));
+    }
+    ok(result)
+}
+
+pub fn set_car<'h>(_hs: &mut GcHeapSession<'h>, mut args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("set-car!", &args, 2)?;
+    let value = args.pop().unwrap();
+    let pair = args.pop().unwrap().as_cons("set-car!")?;
+    pair.set_car(value);
+    ok(Value::Nil)
+}
+
+pub fn set_cdr<'h>(_hs: &mut GcHeapSession<'h>, mut args: Vec<Value<'h>>)
+    -> Result<Trampoline<'h>, String>
+{
+    check_arity("set-cdr!", &args, 2)?;
+    let value = args.pop().unwrap();
+    let pair = args.pop().unwrap().as_cons("set-cdr!")?;
+    pair.set_cdr(value);
+    ok(Value::Nil)
+}
+
+/// Scheme name / implementation pairs for every builtin in this module.
+/// Nothing in this tree builds the global environment yet (there's no
+/// `vm::Environment` setup code to hook into), so these aren't reachable
+/// from Scheme source by themselves -- whatever does build it should
+/// walk this table and `define` each name, rather than re-listing the
+/// same names by hand somewhere else.
+pub const BUILTINS: &[(&str, BuiltinFn)] = &[
+    ("make-hash-table", make_hash_table),
+    ("hash-table-set!", hash_table_set),
+    ("hash-table-ref", hash_table_ref),
+    ("hash-table-delete!", hash_table_delete),
+    ("hash-table-count", hash_table_count),
+    ("hash-table->alist", hash_table_to_alist),
+    ("set-car!", set_car),
+    ("set-cdr!", set_cdr),
+];
@@ -0,0 +1,101 @@
+//! A reusable string/value-interning subsystem.
+//!
+//! `value::InternedString` and `value::InternedStr` used to each hand-roll
+//! their own `HashMap<Arc<str>, u32>` / `Vec<Arc<str>>` pair. That scheme
+//! generalizes cleanly to anything we want deduplicated behind a cheap
+//! `Copy` handle -- symbol names, string literals, and (eventually)
+//! compiled `compile::Code` blobs that happen to be identical -- so it
+//! lives here as `Interner<T>` instead of being copy-pasted per use site.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// A table mapping `T` values to small integer ids and back. Entries are
+/// never removed, so an id (and the `Arc<T>` it names) stays valid for as
+/// long as the `Interner` itself is alive -- callers typically stash one
+/// of these behind a `lazy_static! { static ref ...: Mutex<Interner<T>> }`
+/// to get a single process-wide table.
+pub struct Interner<T: Eq + Hash> {
+    ids: HashMap<Arc<T>, u32>,
+    values: Vec<Arc<T>>,
+}
+
+impl<T: Eq + Hash> Interner<T> {
+    pub fn new() -> Interner<T> {
+        Interner { ids: HashMap::new(), values: Vec::new() }
+    }
+
+    /// Look up `value` in the table, inserting it if it's not already
+    /// present, and return a cheap handle to it.
+    pub fn intern(&mut self, value: T) -> Interned<T> {
+        if let Some(&id) = self.ids.get(&value) {
+            return Interned::new(id);
+        }
+        let value = Arc::new(value);
+        let id = self.values.len() as u32;
+        self.ids.insert(value.clone(), id);
+        self.values.push(value);
+        Interned::new(id)
+    }
+
+    /// Recover the value a handle was interned from.
+    pub fn resolve(&self, handle: Interned<T>) -> Arc<T> {
+        self.values[handle.index as usize].clone()
+    }
+}
+
+/// A cheap, `Copy` handle into an `Interner<T>`: just an index, tagged
+/// with `T` so handles from different interners can't be mixed up at the
+/// type level. Comparing two `Interned<T>`s is one integer compare, with
+/// no pointer chasing or locking.
+///
+/// The `PhantomData<fn() -> T>` (rather than `PhantomData<T>`) makes
+/// `Interned<T>` unconditionally `Send`/`Sync`, since a handle never
+/// actually owns a `T` -- it just names a slot in the table. The trait
+/// impls below are written by hand rather than derived, because `derive`
+/// would require `T` itself to implement each trait.
+pub struct Interned<T> {
+    index: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Interned<T> {
+    fn new(index: u32) -> Interned<T> {
+        Interned { index, marker: PhantomData }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<T> Clone for Interned<T> {
+    fn clone(&self) -> Interned<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Interned<T> {}
+
+impl<T> PartialEq for Interned<T> {
+    fn eq(&self, other: &Interned<T>) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Interned<T> {}
+
+impl<T> Hash for Interned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Interned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Interned({})", self.index)
+    }
+}
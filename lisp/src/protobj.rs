@@ -1,10 +1,25 @@
 use std;
 use std::iter::Iterator;
-use cell_gc::collections::VecRef;
+use cell_gc::collections::{MapRef, VecRef};
 use cell_gc::GcHeapSession;
 use cell_gc::GcLeaf;
 use value::Value;
-use value::InternedString;
+use value::{InternedStr, InternedString};
+
+/// Number of shype transitions (property additions) an object can
+/// accumulate before it's demoted to dictionary mode. Past this point the
+/// shared shype tree would otherwise grow one more transition node per
+/// object, for no shared benefit, since objects used as maps rarely
+/// repeat the same set of property names.
+const DICT_MODE_TRANSITION_THRESHOLD: u32 = 64;
+
+/// A per-object property dictionary, used once an object has either
+/// crossed `DICT_MODE_TRANSITION_THRESHOLD` transitions or had a
+/// property deleted from it. Keeps the `PropDescr` alongside each value
+/// only so dictionary-mode objects still answer the same shape of query
+/// (`get_own_property`-style) as shype-mode ones; the slot number inside
+/// it is meaningless once in dictionary mode.
+pub type PropDict<'h> = MapRef<'h, GcLeaf<InternedString>, (GcLeaf<PropDescr>, Value<'h>)>;
 
 #[derive(Debug, IntoHeap)]
 pub struct Shype<'h> {
@@ -105,7 +120,11 @@ impl<'h> Iterator for ShypeNextSiblingIter<'h> {
 #[derive(Debug, IntoHeap)]
 pub struct Object<'h> {
     pub shype: ShypeRef<'h>,
-    prop_slots: VecRef<'h, Value<'h>>
+    prop_slots: VecRef<'h, Value<'h>>,
+    // `None` while the object is in shype mode. Once set, the object is
+    // in dictionary mode for good: `get_property`/`set_property`/
+    // `own_property_names` consult `dict` instead of walking `shype`.
+    dict: Option<PropDict<'h>>
 }
 
 impl<'h> Object<'h> {
@@ -115,7 +134,8 @@ impl<'h> Object<'h> {
         assert!(prop_slots.len() == 0);
         Object {
             shype: shype,
-            prop_slots: prop_slots
+            prop_slots: prop_slots,
+            dict: None
         }
     }
 }
@@ -127,6 +147,36 @@ impl<'h> ObjectRef<'h> {
         hs.alloc(Object::new(shype, vec))
     }
 
+    pub fn is_dictionary_mode(&self) -> bool {
+        self.dict().is_some()
+    }
+
+    /// Detach this object from the shared shype tree and move its
+    /// properties into a per-object dictionary. A no-op if already in
+    /// dictionary mode.
+    pub fn to_dictionary_mode(&self, hs: &mut GcHeapSession<'h>) {
+        if self.is_dictionary_mode() {
+            return;
+        }
+
+        let dict = MapRef::new(hs);
+        let shype_view = SpecificShypeView::new(self.shype());
+        for anc_shype in shype_view.root_path_iter() {
+            if let ShypeVariant::AddProperty(ref name, ref descr) = anc_shype.variant() {
+                match descr.clone().unwrap() {
+                    PropDescr::Slot(slot) => {
+                        dict.insert(name.clone(), (descr.clone(), self.get_slot(slot)));
+                    }
+                }
+            }
+        }
+        self.set_dict(Some(dict));
+
+        // The properties now live in `dict`; drop the old slots so they
+        // don't keep values alive twice.
+        while self.prop_slots().pop().is_some() {}
+    }
+
     pub fn get_slot(&self, slotno: u32) -> Value<'h> {
         assert!((slotno as usize) < self.prop_slots().len());
         return self.prop_slots().get(slotno as usize).clone();
@@ -353,7 +403,8 @@ impl<'h> SpecificShypeView<'h> {
         let mut result = Vec::new();
         for anc_shype in self.root_path_iter() {
             if let ShypeVariant::AddProperty(ref name, _) = anc_shype.variant() {
-                result.push(Value::ImmString(name.clone()));
+                let name = InternedStr::get(name.clone().unwrap().as_str());
+                result.push(Value::ImmString(GcLeaf::new(name)));
             }
         }
         result
@@ -448,12 +499,21 @@ impl<'h> SpecificObjectView<'h> {
         self.specific_shype_view().get_prototype()
     }
 
+    /// Look up `name`, dispatching on each ancestor's own mode: a
+    /// shype-mode ancestor is consulted by walking its shype, a
+    /// dictionary-mode one by a direct map lookup.
     pub fn get_property(&self, name: &InternedString) -> Value<'h>
     {
         for obj in self.proto_chain_iter() {
-            let mut shype_view = SpecificShypeView::new(obj.shype());
-            if let Some((_, slot)) = shype_view.get_own_property(name) {
-                return obj.get_slot(slot);
+            if let Some(dict) = obj.dict() {
+                if let Some((_, value)) = dict.get(&GcLeaf::new(name.clone())) {
+                    return value;
+                }
+            } else {
+                let mut shype_view = SpecificShypeView::new(obj.shype());
+                if let Some((_, slot)) = shype_view.get_own_property(name) {
+                    return obj.get_slot(slot);
+                }
             }
         }
 
@@ -463,6 +523,22 @@ impl<'h> SpecificObjectView<'h> {
     pub fn set_property(&mut self, name: &InternedString, value: Value<'h>, hs: &mut GcHeapSession<'h>)
         -> ShypeRef<'h>
     {
+        if self.object.is_dictionary_mode() {
+            let dict = self.object.dict().expect("dictionary mode implies a dict");
+            let key = GcLeaf::new(name.clone());
+            let descr = match dict.get(&key) {
+                Some((descr, _)) => descr,
+                None => GcLeaf::new(PropDescr::Slot(0)),
+            };
+            dict.insert(key, (descr, value));
+            return self.object.shype();
+        }
+
+        if self.should_promote_to_dictionary_mode(name) {
+            self.object.to_dictionary_mode(hs);
+            return self.set_property(name, value, hs);
+        }
+
         let mut shype_view = self.specific_shype_view();
         let (shype, slot, add) = shype_view.set_property(self.object.clone(), name, hs);
         assert!(slot <= self.object.num_slots());
@@ -480,6 +556,34 @@ impl<'h> SpecificObjectView<'h> {
         shype
     }
 
+    // Past `DICT_MODE_TRANSITION_THRESHOLD` transitions, adding yet
+    // another property shouldn't grow the shared shype tree by one more
+    // node; overwriting an existing property never grows it regardless.
+    fn should_promote_to_dictionary_mode(&self, name: &InternedString) -> bool {
+        let shype_view = self.specific_shype_view();
+        if shype_view.get_own_property(name).is_some() {
+            return false;
+        }
+        let transitions = shype_view.root_path_iter()
+            .filter(|shype| match shype.variant() {
+                ShypeVariant::AddProperty(..) => true,
+                _ => false,
+            })
+            .count();
+        transitions as u32 >= DICT_MODE_TRANSITION_THRESHOLD
+    }
+
+    /// Remove `name`. In shype mode this forces a one-way transition to
+    /// dictionary mode first (the shype tree has no representation for
+    /// "minus a property"); in dictionary mode it's a direct removal.
+    pub fn delete_property(&mut self, name: &InternedString, hs: &mut GcHeapSession<'h>) {
+        if !self.object.is_dictionary_mode() {
+            self.object.to_dictionary_mode(hs);
+        }
+        let dict = self.object.dict().expect("dictionary mode implies a dict");
+        dict.remove(&GcLeaf::new(name.clone()));
+    }
+
     pub fn become_prototype_of(&mut self, target_shype: ShypeRef<'h>, hs: &mut GcHeapSession<'h>)
         -> ShypeRef<'h>
     {
@@ -514,10 +618,166 @@ impl<'h> SpecificObjectView<'h> {
     }
 
     pub fn has_own_property(&self, name: &InternedString) -> bool {
-        self.specific_shype_view().has_own_property(name)
+        match self.object.dict() {
+            Some(dict) => dict.contains_key(&GcLeaf::new(name.clone())),
+            None => self.specific_shype_view().has_own_property(name),
+        }
     }
 
     pub fn own_property_names(&self) -> Vec<Value<'h>> {
-        self.specific_shype_view().own_property_names()
+        match self.object.dict() {
+            Some(dict) => dict.iter()
+                .map(|(name, _)| {
+                    let name = InternedStr::get(name.unwrap().as_str());
+                    Value::ImmString(GcLeaf::new(name))
+                })
+                .collect(),
+            None => self.specific_shype_view().own_property_names(),
+        }
+    }
+
+    /** Like `get_property`, but consults `cache` first.
+     *
+     * Shypes are immutable transition nodes, so a given `(shype, name)`
+     * always resolves to the same `(depth, slot)` forever -- a cached
+     * entry never needs explicit invalidation, because the only thing
+     * that could change the answer (editing the prototype chain) always
+     * produces a new shype via `set_shype`. But that guarantee only
+     * covers the *receiver*: `become_prototype_of`/`set_prototype` can
+     * repoint an intermediate ancestor's own prototype without touching
+     * the receiver's shype at all, which silently changes what object is
+     * `depth` hops away. So each entry also records the shype of the
+     * ancestor it resolved to, and a hit is only trusted if walking
+     * `depth` hops still lands on an object with that same shype.
+     *
+     * One more guard is needed now that objects can be demoted to
+     * dictionary mode without changing their shype: that demotion clears
+     * `prop_slots`, which would turn a stale cached slot into an
+     * out-of-bounds read, so a hit is only trusted when the resolved
+     * ancestor is still in shype mode.
+     */
+    pub fn get_property_cached(&self, cache: &mut InlineCache<'h>, name: &InternedString)
+        -> Value<'h>
+    {
+        let shype = self.object.shype();
+        if let Some((depth, slot, ancestor_shype)) = cache.lookup(&shype) {
+            // A repointed ancestor can leave the chain shorter than
+            // `depth`; that's a miss, not an error.
+            let mut mb_obj = Some(self.object.clone());
+            for _ in 0..depth {
+                mb_obj = mb_obj.and_then(|obj| SpecificObjectView::new(obj).get_prototype());
+            }
+            if let Some(obj) = mb_obj {
+                if !obj.is_dictionary_mode() && obj.shype() == ancestor_shype {
+                    return obj.get_slot(slot);
+                }
+            }
+        }
+
+        // Miss (or a hit invalidated by a stale ancestor or dictionary-mode
+        // demotion): do the full chain walk, dispatching on each
+        // ancestor's own mode, and remember how far we went and which
+        // ancestor shype we landed on when the answer came from a shype.
+        for (depth, obj) in self.proto_chain_iter().enumerate() {
+            if let Some(dict) = obj.dict() {
+                if let Some((_, value)) = dict.get(&GcLeaf::new(name.clone())) {
+                    return value;
+                }
+            } else {
+                let shype_view = SpecificShypeView::new(obj.shype());
+                if let Some((_, slot)) = shype_view.get_own_property(name) {
+                    cache.insert(shype.clone(), depth as u32, slot, obj.shype());
+                    return obj.get_slot(slot);
+                }
+            }
+        }
+
+        Value::Bool(false)
+    }
+
+    /** Like `set_property`, but consults `cache` first.
+     *
+     * `set_property` only ever touches the receiver's own slots (there's
+     * no setter proto-chain walk in this model), so a cache hit is
+     * always at depth 0: the object's current shype already has the
+     * slot, and we can skip straight to `set_slot` -- unless the object
+     * has since been demoted to dictionary mode (see `get_property_cached`).
+     */
+    pub fn set_property_cached(&mut self, cache: &mut InlineCache<'h>, name: &InternedString,
+                                value: Value<'h>, hs: &mut GcHeapSession<'h>)
+        -> ShypeRef<'h>
+    {
+        let shype = self.object.shype();
+        if !self.object.is_dictionary_mode() {
+            if let Some((0, slot, ancestor_shype)) = cache.lookup(&shype) {
+                if ancestor_shype == shype {
+                    self.object.set_slot(slot, value);
+                    return shype;
+                }
+            }
+        }
+
+        let result_shype = self.set_property(name, value, hs);
+        if !self.object.is_dictionary_mode() {
+            let (_, slot) = self.specific_shype_view().get_own_property(name)
+                .expect("set_property must leave `name` resolvable on the object's new shype");
+            cache.insert(result_shype.clone(), 0, slot, result_shype.clone());
+        }
+        result_shype
+    }
+}
+
+const INLINE_CACHE_CAPACITY: usize = 4;
+
+/// One observation recorded by an [`InlineCache`](struct.InlineCache.html):
+/// "starting from an object with shype `shype`, the property this cache
+/// guards is `depth` prototype hops up, in slot `slot`, on an ancestor
+/// that had shype `ancestor_shype` at the time."
+struct InlineCacheEntry<'h> {
+    shype: ShypeRef<'h>,
+    depth: u32,
+    slot: u32,
+    // The resolved ancestor's own shype, snapshotted when this entry was
+    // recorded. `shype` staying the same only proves the receiver hasn't
+    // changed; an intermediate ancestor can still be repointed to a
+    // different prototype (`become_prototype_of`/`set_prototype`)
+    // without that, so a hit must also recheck this.
+    ancestor_shype: ShypeRef<'h>,
+}
+
+/// A small, reusable inline cache that a VM embeds at a property-access
+/// call site to skip the prototype-chain walk on repeat lookups.
+///
+/// Monomorphic call sites end up with a single entry; polymorphic ones
+/// grow up to `INLINE_CACHE_CAPACITY` entries, evicting least-recently-used
+/// on overflow. A hit compares both the receiver's current `ShypeRef`
+/// against the cached one and the resolved ancestor's current `ShypeRef`
+/// against the one recorded alongside it -- see `get_property_cached` for
+/// why the receiver's shype alone isn't enough.
+pub struct InlineCache<'h> {
+    // Most-recently-used entry first.
+    entries: Vec<InlineCacheEntry<'h>>,
+}
+
+impl<'h> InlineCache<'h> {
+    pub fn new() -> InlineCache<'h> {
+        InlineCache { entries: Vec::with_capacity(INLINE_CACHE_CAPACITY) }
+    }
+
+    fn lookup(&mut self, shype: &ShypeRef<'h>) -> Option<(u32, u32, ShypeRef<'h>)> {
+        let pos = self.entries.iter().position(|e| &e.shype == shype)?;
+        let entry = self.entries.remove(pos);
+        let result = (entry.depth, entry.slot, entry.ancestor_shype.clone());
+        self.entries.insert(0, entry);
+        Some(result)
+    }
+
+    fn insert(&mut self, shype: ShypeRef<'h>, depth: u32, slot: u32, ancestor_shype: ShypeRef<'h>) {
+        if let Some(pos) = self.entries.iter().position(|e| e.shype == shype) {
+            self.entries.remove(pos);
+        } else if self.entries.len() >= INLINE_CACHE_CAPACITY {
+            self.entries.pop();
+        }
+        self.entries.insert(0, InlineCacheEntry { shype, depth, slot, ancestor_shype });
     }
 }
@@ -1,11 +1,11 @@
 use cell_gc::{GcHeapSession, GcLeaf};
-use cell_gc::collections::VecRef;
+use cell_gc::collections::{GcHash, MapRef, VecRef};
 use compile;
-use std::borrow::Borrow;
+use intern::{Interned, Interner};
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::mem;
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
-use std::collections::HashSet;
 use vm::{EnvironmentRef, Trampoline};
 
 #[derive(Debug, IntoHeap)]
@@ -20,13 +20,14 @@ pub enum Value<'h> {
     Bool(bool),
     Int(i32),
     Symbol(GcLeaf<InternedString>),
-    ImmString(GcLeaf<InternedString>),
+    ImmString(GcLeaf<InternedStr>),
     Lambda(PairRef<'h>),
     Code(compile::CodeRef<'h>),
     Builtin(GcLeaf<BuiltinFnPtr>),
     Cons(PairRef<'h>),
     Vector(VecRef<'h, Value<'h>>),
     Environment(EnvironmentRef<'h>),
+    HashTable(MapRef<'h, Value<'h>, Value<'h>>),
 }
 
 pub use self::Value::*;
@@ -60,8 +61,6 @@ impl fmt::Debug for BuiltinFnPtr {
 
 impl<'h> fmt::Display for Value<'h> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // Note that this will need to add a set of already-printed pairs if we add
-        // `set-car!` and/or `set-cdr!` and introduce the possibility of cycles.
         match *self {
             Nil => write!(f, "nil"),
             Bool(true) => write!(f, "#t"),
@@ -74,7 +73,7 @@ impl<'h> fmt::Display for Value<'h> {
             Builtin(_) => write!(f, "#builtin"),
             Cons(ref p) => {
                 write!(f, "(")?;
-                write_pair(f, p.clone())?;
+                write_pair(f, p.clone(), &mut Vec::new())?;
                 write!(f, ")")
             }
             Vector(ref v) => {
@@ -88,17 +87,29 @@ impl<'h> fmt::Display for Value<'h> {
                 write!(f, ")")
             }
             Environment(_) => write!(f, "#environment"),
+            HashTable(_) => write!(f, "#<hash-table>"),
         }
     }
 }
 
-fn write_pair<'h>(f: &mut fmt::Formatter, pair: PairRef<'h>) -> fmt::Result {
+/// Prints the elements of `pair` and everything it's linked to, space
+/// separated. `seen` is the set of pairs already printed during this
+/// call to `Display::fmt`, by heap identity -- `set-car!`/`set-cdr!` can
+/// link a pair back into its own tail, and without this we'd recurse
+/// until the stack overflows. On revisiting a pair we print `...`
+/// instead of recursing into it again.
+fn write_pair<'h>(f: &mut fmt::Formatter, pair: PairRef<'h>, seen: &mut Vec<PairRef<'h>>) -> fmt::Result {
+    if seen.iter().any(|p| *p == pair) {
+        return write!(f, "...");
+    }
+    seen.push(pair.clone());
+
     write!(f, "{}", pair.car())?;
     match pair.cdr() {
         Nil => Ok(()),
         Cons(p) => {
             write!(f, " ")?;
-            write_pair(f, p)
+            write_pair(f, p, seen)
         }
         otherwise => {
             write!(f, " . ")?;
@@ -165,6 +176,13 @@ impl<'h> Value<'h> {
         }
     }
 
+    pub fn as_cons(self, error_msg: &str) -> Result<PairRef<'h>, String> {
+        match self {
+            Cons(r) => Ok(r),
+            _ => Err(format!("{}: pair expected", error_msg)),
+        }
+    }
+
     pub fn is_vector(&self) -> bool {
         match *self {
             Vector(_) => true,
@@ -200,7 +218,7 @@ impl<'h> Value<'h> {
         }
     }
 
-    pub fn as_string(self, error_msg: &str) -> Result<InternedString, String> {
+    pub fn as_string(self, error_msg: &str) -> Result<InternedStr, String> {
         match self {
             ImmString(s) => Ok(s.unwrap()),
             _ => Err(error_msg.to_string()),
@@ -214,103 +232,220 @@ impl<'h> Value<'h> {
             _ => false,
         }
     }
+
+    pub fn is_hash_table(&self) -> bool {
+        match *self {
+            HashTable(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn as_hash_table(self, error_msg: &str) -> Result<MapRef<'h, Value<'h>, Value<'h>>, String> {
+        match self {
+            HashTable(m) => Ok(m),
+            _ => Err(format!("{}: hash table expected", error_msg)),
+        }
+    }
+
+    /// `HashTable` keys are restricted to the immediate `Value` cases
+    /// that have a stable identity to hash: symbols, integers, booleans,
+    /// and interned strings. Pairs, vectors, lambdas, and the like don't
+    /// have a sensible eqv-style hash, so they're rejected up front by
+    /// the hash-table builtins rather than accepted and silently hashed
+    /// by heap-pointer identity.
+    pub fn is_hashable_key(&self) -> bool {
+        match *self {
+            Symbol(_) | ImmString(_) | Int(_) | Bool(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Walk this value as the head of a (possibly improper, possibly
+    /// circular) list. `set-car!`/`set-cdr!` make it possible to build a
+    /// list that loops back on itself, so plain one-pointer iteration
+    /// isn't safe here; `ListIter` guards against that.
+    pub fn into_list_iter(self) -> ListIter<'h> {
+        ListIter { fast: self.clone(), slow: self, tick: false }
+    }
 }
 
-impl<'h> Iterator for Value<'h> {
+/// Iterates the elements of a list `Value`, detecting cycles with
+/// Floyd's tortoise-and-hare: `fast` advances one element per `next()`
+/// call, `slow` advances one element every other call, and if they're
+/// ever on the same pair, the list loops back on itself, so iteration
+/// stops with an error instead of spinning forever.
+pub struct ListIter<'h> {
+    fast: Value<'h>,
+    slow: Value<'h>,
+    tick: bool,
+}
+
+impl<'h> Iterator for ListIter<'h> {
     type Item = Result<Value<'h>, String>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (car, cdr) = match *self {
+        let pair = match self.fast {
             Nil => return None,
-            Cons(ref pair) => (pair.car(), pair.cdr()),
+            Cons(ref pair) => pair.clone(),
             _ => return Some(Err("improper list".into())),
         };
-        *self = cdr;
+        let car = pair.car();
+        self.fast = pair.cdr();
+
+        self.tick = !self.tick;
+        if self.tick {
+            if let Cons(ref slow_pair) = self.slow {
+                let slow_pair = slow_pair.clone();
+                self.slow = slow_pair.cdr();
+                if let Cons(ref fast_pair) = self.fast {
+                    if *fast_pair == slow_pair {
+                        self.fast = Nil;
+                        return Some(Err("circular list".into()));
+                    }
+                }
+            }
+        }
+
         Some(Ok(car))
     }
 }
 
 
-#[derive(Clone, Debug)]
-pub struct InternedString(Arc<String>);
+/// A cheap, `Copy` handle to an interned symbol name: just an index into
+/// the global `SYMBOLS` table below. Comparing two `InternedString`s (for
+/// equality, in a `HashMap`, in the hot environment-lookup path, ...) is
+/// one integer compare, with no pointer chasing.
+///
+/// This and `InternedStr` are both thin, domain-specific wrappers around
+/// `intern::Interned<String>` -- one handle per table, so a symbol handle
+/// and a string handle can never be mixed up even though both ultimately
+/// index a `Vec<Arc<String>>` of the same shape.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternedString(Interned<String>);
 
-// Note: If we ever impl Hash for InternedString, it will be better to use a
-// custom pointer-based implementation than to use derive(Hash), which would
-// hash the contents of the string.
-impl PartialEq for InternedString {
-    fn eq(&self, other: &InternedString) -> bool {
-        Arc::ptr_eq(&self.0, &other.0)
+impl fmt::Debug for InternedString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InternedString({:?})", self.as_str())
     }
 }
 
-impl Eq for InternedString {}
-
 lazy_static! {
-    static ref STRINGS: Mutex<HashSet<InternedStringByValue>> = Mutex::new(HashSet::new());
+    static ref SYMBOLS: Mutex<Interner<String>> = Mutex::new(Interner::new());
 }
 
 static GENSYM_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
 
-#[derive(Eq, Hash, PartialEq)]
-struct InternedStringByValue(Arc<String>);
-
-impl Borrow<str> for InternedStringByValue {
-    fn borrow(&self) -> &str {
-        &self.0
-    }
-}
-
 impl InternedString {
-    /// Return an InternedString that is not interned.
+    /// Return an `InternedString` for a name that's guaranteed not to
+    /// collide with any existing (or future, hand-written) name.
     pub fn gensym() -> InternedString {
         let n = GENSYM_COUNT.fetch_add(1, Ordering::SeqCst);
-        InternedString(Arc::new(format!("#<gensym{}>", n)))
+        let name = format!("#<gensym{}>", n);
+        InternedString(SYMBOLS.lock().unwrap().intern(name))
     }
 
     pub fn get(s: &str) -> InternedString {
-        let mut guard = STRINGS.lock().unwrap();
-        if let Some(x) = guard.get(s) {
-            return InternedString(x.0.clone());
-        }
-        let s = Arc::new(s.to_string());
-        guard.insert(InternedStringByValue(s.clone()));
-        InternedString(s)
+        InternedString(SYMBOLS.lock().unwrap().intern(s.to_string()))
     }
 
+    /// Historically this promoted a not-yet-interned gensym into the
+    /// global table; a gensym's name is already registered (under its
+    /// own unique id) the moment it's created, so there's nothing left
+    /// to do here.
     pub fn really_intern(self) -> InternedString {
-        if !self.0.starts_with("#<gensym") {
-            return self;
-        }
-        let mut guard = STRINGS.lock().unwrap();
-        {
-            let s: &str = &self.0;
-            match guard.get(s) {
-                Some(interned) => return InternedString(interned.0.clone()),
-                None => {}
-            }
-        }
-
-        // Don't cause other references to this string to become interned!
-        let new_arc = {
-            let s: &str = &self.0;
-            Arc::new(s.to_string())
-        };
-        guard.insert(InternedStringByValue(new_arc));
         self
     }
 
-    pub fn as_str(&self) -> &str {
-        &self.0
+    pub fn as_str(&self) -> &'static str {
+        let guard = SYMBOLS.lock().unwrap();
+        let name = guard.resolve(self.0);
+        let s: &str = &name;
+        // Safety: entries are never removed from `Interner`, so the
+        // `Arc<String>` this resolves to (and the string data it points
+        // to) stays alive for the process's whole lifetime, even after
+        // `name`, this clone of it, is dropped at the end of the call.
+        unsafe { mem::transmute::<&str, &'static str>(s) }
+    }
+
+    pub fn is_gensym(&self) -> bool {
+        self.as_str().starts_with("#<gensym")
+    }
+
+    pub fn is_interned(&self) -> bool { !self.is_gensym() }
+}
+
+// `MapRef` keys need equality/hashing. Hash on the index directly -- it's
+// already the canonical, collision-free identity for this name.
+impl GcHash for GcLeaf<InternedString> {
+    fn gc_hash(&self) -> u64 {
+        self.clone().unwrap().0.index() as u64
     }
 
-    pub fn is_interned(&self) -> bool {
+    fn gc_eq(&self, other: &GcLeaf<InternedString>) -> bool {
+        self.clone().unwrap() == other.clone().unwrap()
+    }
+}
+
+/// A cheap, `Copy` handle to an interned string literal, backed by its
+/// own `STRINGS` table (kept separate from `SYMBOLS` so a `Value::Symbol`
+/// and a `Value::ImmString` that happen to spell the same name never
+/// collide, and so the two domains can grow or get replaced -- e.g. with
+/// ropes or a different string-literal GC policy -- independently).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternedStr(Interned<String>);
+
+impl fmt::Debug for InternedStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InternedStr({:?})", self.as_str())
+    }
+}
+
+lazy_static! {
+    static ref STRINGS: Mutex<Interner<String>> = Mutex::new(Interner::new());
+}
+
+impl InternedStr {
+    pub fn get(s: &str) -> InternedStr {
+        InternedStr(STRINGS.lock().unwrap().intern(s.to_string()))
+    }
+
+    pub fn as_str(&self) -> &'static str {
         let guard = STRINGS.lock().unwrap();
-        let s: &str = &self.0;
-        match guard.get(s) {
-            None => false,
-            Some(interned) => Arc::ptr_eq(&interned.0, &self.0)
+        let name = guard.resolve(self.0);
+        let s: &str = &name;
+        // Safety: see `InternedString::as_str` above -- same table
+        // discipline, same argument.
+        unsafe { mem::transmute::<&str, &'static str>(s) }
+    }
+}
+
+impl GcHash for GcLeaf<InternedStr> {
+    fn gc_hash(&self) -> u64 {
+        self.clone().unwrap().0.index() as u64
+    }
+
+    fn gc_eq(&self, other: &GcLeaf<InternedStr>) -> bool {
+        self.clone().unwrap() == other.clone().unwrap()
+    }
+}
+
+/// Lets `Value::HashTable` use `Value` itself as the key type. Only
+/// called for keys that pass `is_hashable_key`; callers (the
+/// `hash-table-*` builtins) are responsible for checking that first, the
+/// same way `as_int`/`as_pair`/etc. push their type checks to the call
+/// site rather than this trait.
+impl<'h> GcHash for Value<'h> {
+    fn gc_hash(&self) -> u64 {
+        match *self {
+            Symbol(ref s) => s.gc_hash(),
+            ImmString(ref s) => s.gc_hash(),
+            Int(n) => n as u64,
+            Bool(b) => b as u64,
+            _ => panic!("not a hashable Value: {:?}", self),
         }
     }
 
-    pub fn is_gensym(&self) -> bool { !self.is_interned() }
+    fn gc_eq(&self, other: &Value<'h>) -> bool {
+        self == other
+    }
 }
\ No newline at end of file
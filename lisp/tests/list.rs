@@ -0,0 +1,47 @@
+//! Tests for `Value`'s cycle-safe `Display` and `ListIter`.
+
+extern crate cell_gc;
+extern crate lisp;
+
+use lisp::value::{Pair, Value};
+
+#[test]
+fn display_stops_at_a_pair_it_has_already_printed() {
+    cell_gc::with_heap(|hs| {
+        let tail = hs.alloc(Pair { car: Value::Int(2), cdr: Value::Nil });
+        let head = hs.alloc(Pair { car: Value::Int(1), cdr: Value::Cons(tail.clone()) });
+        // `set-car!`/`set-cdr!` can link a pair back into its own tail;
+        // `Display` must print `...` on the second visit instead of
+        // recursing forever.
+        tail.set_cdr(Value::Cons(head.clone()));
+
+        assert_eq!(format!("{}", Value::Cons(head)), "(1 2 ...)");
+    });
+}
+
+#[test]
+fn list_iter_yields_each_element_in_order() {
+    cell_gc::with_heap(|hs| {
+        let tail = hs.alloc(Pair { car: Value::Int(2), cdr: Value::Nil });
+        let head = hs.alloc(Pair { car: Value::Int(1), cdr: Value::Cons(tail) });
+
+        let items: Result<Vec<Value>, String> = Value::Cons(head).into_list_iter().collect();
+        assert_eq!(items.unwrap(), vec![Value::Int(1), Value::Int(2)]);
+    });
+}
+
+#[test]
+fn list_iter_reports_an_error_instead_of_looping_forever_on_a_cycle() {
+    cell_gc::with_heap(|hs| {
+        let tail = hs.alloc(Pair { car: Value::Int(2), cdr: Value::Nil });
+        let head = hs.alloc(Pair { car: Value::Int(1), cdr: Value::Cons(tail.clone()) });
+        tail.set_cdr(Value::Cons(head.clone()));
+
+        let err = Value::Cons(head)
+            .into_list_iter()
+            .take(10)
+            .collect::<Result<Vec<Value>, String>>()
+            .unwrap_err();
+        assert_eq!(err, "circular list");
+    });
+}
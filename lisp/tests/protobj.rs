@@ -0,0 +1,49 @@
+//! Tests for `InlineCache` invalidation across multi-hop prototype chains.
+
+extern crate cell_gc;
+extern crate lisp;
+
+use lisp::protobj::{InlineCache, ObjectRef, Shype, SpecificObjectView};
+use lisp::value::{InternedString, Value};
+
+#[test]
+fn cache_hit_revalidates_an_ancestor_repointed_past_the_receiver() {
+    cell_gc::with_heap(|hs| {
+        let name = InternedString::get("x");
+
+        let c = ObjectRef::allocate(hs, hs.alloc(Shype::new_root()));
+        SpecificObjectView::new(c.clone()).set_property(&name, Value::Int(42), hs);
+
+        let b = ObjectRef::allocate(hs, hs.alloc(Shype::new_root()));
+        SpecificObjectView::new(b.clone()).set_prototype(c.clone(), hs);
+
+        let a = ObjectRef::allocate(hs, hs.alloc(Shype::new_root()));
+        SpecificObjectView::new(a.clone()).set_prototype(b.clone(), hs);
+
+        let mut cache = InlineCache::new();
+        // First call walks A -> B -> C (depth 2) and records the hit.
+        assert_eq!(
+            SpecificObjectView::new(a.clone()).get_property_cached(&mut cache, &name),
+            Value::Int(42)
+        );
+        // Second call hits the cache; nothing has changed, so it must agree.
+        assert_eq!(
+            SpecificObjectView::new(a.clone()).get_property_cached(&mut cache, &name),
+            Value::Int(42)
+        );
+
+        // Repoint B's own prototype away from C, to an unrelated object
+        // with no "x" property (and no slots at all). This doesn't touch
+        // A's shype -- A's own SetPrototype(B) shype is unaffected -- so
+        // a cache keyed only on the receiver's shype would still "hit"
+        // and walk 2 hops straight into `d.get_slot(slot)`, which would
+        // either read garbage or panic on an out-of-bounds slot.
+        let d = ObjectRef::allocate(hs, hs.alloc(Shype::new_root()));
+        SpecificObjectView::new(b.clone()).set_prototype(d, hs);
+
+        assert_eq!(
+            SpecificObjectView::new(a).get_property_cached(&mut cache, &name),
+            Value::Bool(false)
+        );
+    });
+}
@@ -0,0 +1,518 @@
+//! GC-managed collection types.
+//!
+//! These wrap a GC heap allocation with an ordinary Rust container API
+//! (`Vec`-like, `HashMap`-like, ...) so that a VM can store dynamically
+//! sized data without forcing every field onto the Rust heap. Elements
+//! are traced during collection just like the fields of any other
+//! `gc_heap_type!`-declared struct.
+
+use std::cell::RefCell;
+use std::fmt;
+
+use gcref::GCRef;
+use heap::GcHeapSession;
+use traits::{IntoHeap, IntoHeapAllocation, Tracer};
+
+/// A growable vector living in the GC heap.
+///
+/// Allocate one with `heap.alloc(Vec::new())`. `VecRef` is `Clone`
+/// (like `RefIntList` and friends) but not `Copy`; cloning it copies
+/// the reference, not the underlying vector.
+pub struct VecRef<'h, T: IntoHeap<'h>> {
+    storage: GCRef<'h, RefCell<Vec<T>>>,
+}
+
+impl<'h, T: IntoHeap<'h>> Clone for VecRef<'h, T> {
+    fn clone(&self) -> VecRef<'h, T> {
+        VecRef { storage: self.storage.clone() }
+    }
+}
+
+// Identity, like every other `*Ref` handle in this crate: two `VecRef`s
+// are equal exactly when they point at the same heap allocation.
+impl<'h, T: IntoHeap<'h>> PartialEq for VecRef<'h, T> {
+    fn eq(&self, other: &VecRef<'h, T>) -> bool {
+        self.storage == other.storage
+    }
+}
+
+impl<'h, T: IntoHeap<'h>> fmt::Debug for VecRef<'h, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VecRef {{ len: {} }}", self.storage.borrow().len())
+    }
+}
+
+unsafe impl<'h, T: IntoHeap<'h>> IntoHeapAllocation<'h> for Vec<T> {
+    type Ref = VecRef<'h, T>;
+
+    fn wrap_gcref(gcref: GCRef<'h, RefCell<Vec<T>>>) -> VecRef<'h, T> {
+        VecRef { storage: gcref }
+    }
+}
+
+impl<'h, T: IntoHeap<'h> + Clone> VecRef<'h, T> {
+    pub fn len(&self) -> usize {
+        self.storage.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        self.storage.borrow()[index].clone()
+    }
+
+    pub fn set(&self, index: usize, value: T) {
+        self.storage.borrow_mut()[index] = value;
+    }
+
+    pub fn push(&self, value: T) {
+        self.storage.borrow_mut().push(value);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.storage.borrow_mut().pop()
+    }
+
+    pub fn remove(&self, index: usize) -> T {
+        self.storage.borrow_mut().remove(index)
+    }
+
+    pub fn insert(&self, index: usize, value: T) {
+        self.storage.borrow_mut().insert(index, value);
+    }
+
+    pub fn iter(&self) -> VecRefIter<'h, T> {
+        VecRefIter { vec: self.clone(), index: 0 }
+    }
+}
+
+/// Iterator over the elements of a [`VecRef`](struct.VecRef.html).
+///
+/// Cloning elements out one at a time (rather than borrowing the
+/// backing `Vec` for the lifetime of the iterator) keeps this safe even
+/// if the heap is mutated between calls to `next()`.
+pub struct VecRefIter<'h, T: IntoHeap<'h>> {
+    vec: VecRef<'h, T>,
+    index: usize,
+}
+
+impl<'h, T: IntoHeap<'h> + Clone> Iterator for VecRefIter<'h, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.index < self.vec.len() {
+            let item = self.vec.get(self.index);
+            self.index += 1;
+            Some(item)
+        } else {
+            None
+        }
+    }
+}
+
+/// A fixed-capacity ring buffer living in the GC heap: once `limit`
+/// elements are pushed, pushing another evicts one from the opposite end,
+/// in O(1) -- unlike a plain `VecRef`, which would need an O(n)
+/// `insert(0, _)`/`remove(0)` shuffle to evict from the front.
+///
+/// Backed by a `VecRef` of exactly `limit` slots, `None` until filled;
+/// `head` and `len` locate the live elements within it without ever
+/// physically reordering them.
+pub struct BoundedVecRef<'h, T: IntoHeap<'h>> {
+    slots: VecRef<'h, Option<T>>,
+    head: RefCell<usize>,
+    len: RefCell<usize>,
+}
+
+impl<'h, T: IntoHeap<'h>> Clone for BoundedVecRef<'h, T> {
+    fn clone(&self) -> BoundedVecRef<'h, T> {
+        BoundedVecRef {
+            slots: self.slots.clone(),
+            head: RefCell::new(*self.head.borrow()),
+            len: RefCell::new(*self.len.borrow()),
+        }
+    }
+}
+
+// Identity, like every other `*Ref` handle in this crate.
+impl<'h, T: IntoHeap<'h>> PartialEq for BoundedVecRef<'h, T> {
+    fn eq(&self, other: &BoundedVecRef<'h, T>) -> bool {
+        self.slots == other.slots
+    }
+}
+
+impl<'h, T: IntoHeap<'h>> fmt::Debug for BoundedVecRef<'h, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BoundedVecRef {{ len: {}, limit: {} }}", self.len(), self.limit())
+    }
+}
+
+impl<'h, T: IntoHeap<'h> + Clone> BoundedVecRef<'h, T> {
+    /// Allocate a new, empty ring buffer that holds at most `limit`
+    /// elements at once.
+    pub fn new(hs: &mut GcHeapSession<'h>, limit: usize) -> BoundedVecRef<'h, T> {
+        let slots = hs.alloc(Vec::new());
+        for _ in 0..limit {
+            slots.push(None);
+        }
+        BoundedVecRef { slots, head: RefCell::new(0), len: RefCell::new(0) }
+    }
+
+    pub fn len(&self) -> usize {
+        *self.len.borrow()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The maximum number of elements this buffer can hold at once.
+    pub fn limit(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Translate a position counted from the front (0 = oldest element)
+    // into an index into `slots`.
+    fn slot_index(&self, offset: usize) -> usize {
+        (*self.head.borrow() + offset) % self.limit()
+    }
+
+    /// Push `value` onto the back, evicting the front element in O(1) if
+    /// the buffer is already at `limit`. A no-op if `limit` is 0.
+    pub fn push_back(&self, value: T) {
+        if self.limit() == 0 {
+            return;
+        }
+        if self.len() == self.limit() {
+            self.pop_front();
+        }
+        let index = self.slot_index(self.len());
+        self.slots.set(index, Some(value));
+        *self.len.borrow_mut() += 1;
+    }
+
+    /// Push `value` onto the front, evicting the back element in O(1) if
+    /// the buffer is already at `limit`. A no-op if `limit` is 0.
+    pub fn push_front(&self, value: T) {
+        if self.limit() == 0 {
+            return;
+        }
+        if self.len() == self.limit() {
+            self.pop_back();
+        }
+        let limit = self.limit();
+        let new_head = (*self.head.borrow() + limit - 1) % limit;
+        *self.head.borrow_mut() = new_head;
+        self.slots.set(new_head, Some(value));
+        *self.len.borrow_mut() += 1;
+    }
+
+    /// Remove and return the front (oldest) element, if any.
+    pub fn pop_front(&self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = self.slot_index(0);
+        let value = self.slots.get(index);
+        self.slots.set(index, None);
+        *self.head.borrow_mut() = (*self.head.borrow() + 1) % self.limit();
+        *self.len.borrow_mut() -= 1;
+        value
+    }
+
+    /// Remove and return the back (newest) element, if any.
+    pub fn pop_back(&self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = self.slot_index(self.len() - 1);
+        let value = self.slots.get(index);
+        self.slots.set(index, None);
+        *self.len.borrow_mut() -= 1;
+        value
+    }
+
+    /// Iterate the live elements from front (oldest) to back (newest).
+    pub fn iter(&self) -> BoundedVecRefIter<'h, T> {
+        BoundedVecRefIter { ring: self.clone(), index: 0 }
+    }
+}
+
+/// Iterator over the elements of a
+/// [`BoundedVecRef`](struct.BoundedVecRef.html), from front to back.
+pub struct BoundedVecRefIter<'h, T: IntoHeap<'h>> {
+    ring: BoundedVecRef<'h, T>,
+    index: usize,
+}
+
+impl<'h, T: IntoHeap<'h> + Clone> Iterator for BoundedVecRefIter<'h, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.index >= self.ring.len() {
+            return None;
+        }
+        let index = self.ring.slot_index(self.index);
+        self.index += 1;
+        match self.ring.slots.get(index) {
+            Some(value) => Some(value),
+            None => unreachable!("a slot within len must hold a value"),
+        }
+    }
+}
+
+/// Equality and hashing for `MapRef` keys.
+///
+/// Ordinary `std::hash::Hash`/`Eq` don't fit GC refs, so `MapRef` uses
+/// this small trait instead. Implement it for primitives by hashing/
+/// comparing the value, and for GC refs by hashing/comparing identity.
+pub trait GcHash {
+    fn gc_hash(&self) -> u64;
+    fn gc_eq(&self, other: &Self) -> bool;
+}
+
+impl GcHash for i32 {
+    fn gc_hash(&self) -> u64 { *self as u64 }
+    fn gc_eq(&self, other: &i32) -> bool { self == other }
+}
+
+impl GcHash for bool {
+    fn gc_hash(&self) -> u64 { *self as u64 }
+    fn gc_eq(&self, other: &bool) -> bool { self == other }
+}
+
+#[derive(Clone)]
+enum Bucket<K, V> {
+    Empty,
+    // A slot that once held an entry but was vacated by `remove`; probing
+    // must keep scanning past these, so they're distinct from `Empty`.
+    Tombstone,
+    Occupied(K, V),
+}
+
+/// Error returned by [`MapRef::try_insert`](struct.MapRef.html#method.try_insert)
+/// when the key is already present. Carries the value already stored
+/// under that key, so the caller doesn't need a second lookup to see
+/// what blocked the insert.
+#[derive(Debug)]
+pub struct OccupiedError<V>(pub V);
+
+const MIN_CAPACITY: usize = 8;
+
+/// A hash map living in the GC heap, backed by open addressing over a
+/// `VecRef` bucket array so every slot is visited by the tracer.
+pub struct MapRef<'h, K: IntoHeap<'h>, V: IntoHeap<'h>> {
+    buckets: VecRef<'h, Bucket<K, V>>,
+    len: RefCell<usize>,
+}
+
+// A hand-written impl rather than `#[derive(IntoHeap)]`: `Bucket` needs
+// its `Empty`/`Tombstone` variants to carry no in-heap payload at all
+// (so an all-`Empty` bucket array can be grown by pushing plain
+// variants, with no `K`/`V` to marshal), which the derive doesn't
+// support for enum variants with zero fields mixed with ones that have
+// a payload tied to the same type parameters.
+unsafe impl<'h, K: IntoHeap<'h>, V: IntoHeap<'h>> IntoHeap<'h> for Bucket<K, V> {
+    type In = Bucket<K::In, V::In>;
+
+    unsafe fn into_heap(self) -> Self::In {
+        match self {
+            Bucket::Empty => Bucket::Empty,
+            Bucket::Tombstone => Bucket::Tombstone,
+            Bucket::Occupied(k, v) => Bucket::Occupied(k.into_heap(), v.into_heap()),
+        }
+    }
+
+    unsafe fn from_heap(heap: &Self::In) -> Self {
+        match *heap {
+            Bucket::Empty => Bucket::Empty,
+            Bucket::Tombstone => Bucket::Tombstone,
+            Bucket::Occupied(ref k, ref v) => Bucket::Occupied(K::from_heap(k), V::from_heap(v)),
+        }
+    }
+
+    unsafe fn mark(heap: &Self::In, tracer: &mut Tracer) {
+        if let Bucket::Occupied(ref k, ref v) = *heap {
+            K::mark(k, tracer);
+            V::mark(v, tracer);
+        }
+    }
+}
+
+impl<'h, K, V> Clone for MapRef<'h, K, V>
+    where K: IntoHeap<'h>, V: IntoHeap<'h>
+{
+    fn clone(&self) -> MapRef<'h, K, V> {
+        MapRef { buckets: self.buckets.clone(), len: RefCell::new(*self.len.borrow()) }
+    }
+}
+
+// Identity, like `VecRef`: two `MapRef`s are equal exactly when they
+// share the same backing bucket array.
+impl<'h, K, V> PartialEq for MapRef<'h, K, V>
+    where K: IntoHeap<'h>, V: IntoHeap<'h>
+{
+    fn eq(&self, other: &MapRef<'h, K, V>) -> bool {
+        self.buckets == other.buckets
+    }
+}
+
+impl<'h, K, V> fmt::Debug for MapRef<'h, K, V>
+    where K: IntoHeap<'h>, V: IntoHeap<'h>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MapRef {{ len: {} }}", self.len())
+    }
+}
+
+impl<'h, K, V> MapRef<'h, K, V>
+    where K: IntoHeap<'h> + Clone + GcHash, V: IntoHeap<'h> + Clone
+{
+    /// Allocate a new, empty `MapRef`.
+    pub fn new(hs: &mut GcHeapSession<'h>) -> MapRef<'h, K, V> {
+        let buckets = hs.alloc(Vec::new());
+        for _ in 0..MIN_CAPACITY {
+            buckets.push(Bucket::Empty);
+        }
+        MapRef { buckets, len: RefCell::new(0) }
+    }
+
+    pub fn len(&self) -> usize {
+        *self.len.borrow()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn capacity(&self) -> usize {
+        self.buckets.len()
+    }
+
+    // Find the bucket index for `key`: the occupied slot holding it if
+    // it's present, otherwise the first `Empty`/`Tombstone` slot a fresh
+    // insert may use. A `Tombstone` only ends the search if `key` is
+    // nowhere else in the probe sequence -- `key` may have been inserted
+    // *past* it after a collision, so lookups must keep scanning through
+    // tombstones rather than stopping at the first one, the same way
+    // `remove` must not let them look like the end of the chain.
+    fn probe(&self, key: &K) -> usize {
+        let cap = self.capacity();
+        let mut index = (key.gc_hash() as usize) % cap;
+        let mut first_tombstone = None;
+        loop {
+            match self.buckets.get(index) {
+                Bucket::Empty => return first_tombstone.unwrap_or(index),
+                Bucket::Tombstone => {
+                    if first_tombstone.is_none() {
+                        first_tombstone = Some(index);
+                    }
+                    index = (index + 1) % cap;
+                }
+                Bucket::Occupied(ref k, _) if k.gc_eq(key) => return index,
+                Bucket::Occupied(..) => index = (index + 1) % cap,
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        match self.buckets.get(self.probe(key)) {
+            Bucket::Occupied(_, v) => Some(v),
+            Bucket::Empty | Bucket::Tombstone => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert `key`/`value`, overwriting any previous value for `key`.
+    /// Returns the previous value, if any.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        if (self.len() + 1) * 2 > self.capacity() {
+            self.grow();
+        }
+        let index = self.probe(&key);
+        let old = match self.buckets.get(index) {
+            Bucket::Occupied(_, v) => Some(v),
+            Bucket::Empty | Bucket::Tombstone => {
+                *self.len.borrow_mut() += 1;
+                None
+            }
+        };
+        self.buckets.set(index, Bucket::Occupied(key, value));
+        old
+    }
+
+    /// Insert `key`/`value` only if `key` is not already present.
+    /// Leaves the map unchanged and returns `Err(OccupiedError(existing))`
+    /// when it is.
+    pub fn try_insert(&self, key: K, value: V) -> Result<(), OccupiedError<V>> {
+        if let Some(existing) = self.get(&key) {
+            return Err(OccupiedError(existing));
+        }
+        self.insert(key, value);
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let index = self.probe(key);
+        match self.buckets.get(index) {
+            Bucket::Occupied(_, v) => {
+                self.buckets.set(index, Bucket::Tombstone);
+                *self.len.borrow_mut() -= 1;
+                Some(v)
+            }
+            Bucket::Empty | Bucket::Tombstone => None,
+        }
+    }
+
+    pub fn iter(&self) -> MapRefIter<'h, K, V> {
+        MapRefIter { map: self.clone(), index: 0 }
+    }
+
+    // Double the bucket array (by pushing more `Empty` slots onto the
+    // same `VecRef`, so no new heap allocation/session is needed) and
+    // rehash every live entry into it. `probe` reads `self.capacity()`
+    // fresh each call, so it naturally spreads entries across the grown
+    // table.
+    fn grow(&self) {
+        let old_cap = self.capacity();
+        let live: Vec<(K, V)> = self.iter().collect();
+        for _ in 0..old_cap {
+            self.buckets.push(Bucket::Empty);
+        }
+        for i in 0..old_cap {
+            self.buckets.set(i, Bucket::Empty);
+        }
+        for (k, v) in live {
+            let index = self.probe(&k);
+            self.buckets.set(index, Bucket::Occupied(k, v));
+        }
+    }
+}
+
+/// Iterator over the key/value pairs of a [`MapRef`](struct.MapRef.html).
+pub struct MapRefIter<'h, K: IntoHeap<'h>, V: IntoHeap<'h>> {
+    map: MapRef<'h, K, V>,
+    index: usize,
+}
+
+impl<'h, K, V> Iterator for MapRefIter<'h, K, V>
+    where K: IntoHeap<'h> + Clone + GcHash, V: IntoHeap<'h> + Clone
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        while self.index < self.map.capacity() {
+            let i = self.index;
+            self.index += 1;
+            if let Bucket::Occupied(k, v) = self.map.buckets.get(i) {
+                return Some((k, v));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,87 @@
+//! Tests for the GC-managed collections in `cell_gc::collections`.
+
+extern crate cell_gc;
+
+use cell_gc::collections::{BoundedVecRef, MapRef, OccupiedError};
+
+#[test]
+fn map_ref_probes_past_tombstones() {
+    cell_gc::with_heap(|hs| {
+        let map: MapRef<i32, i32> = MapRef::new(hs);
+
+        // `3` and `11` collide in the 8-bucket table `MapRef::new`
+        // starts with (`11 % 8 == 3 % 8 == 3`): `11` probes to slot 3,
+        // finds it occupied by `3`, and lands at slot 4.
+        map.insert(3, 100);
+        map.insert(11, 200);
+        assert_eq!(map.get(&11), Some(200));
+
+        // Deleting `3` turns slot 3 into a tombstone. Looking up `11`
+        // must probe *past* it to reach slot 4, not stop there.
+        map.remove(&3);
+        assert_eq!(map.get(&11), Some(200));
+        assert_eq!(map.len(), 1);
+
+        // Re-inserting `11` must overwrite the existing entry in slot
+        // 4, not land in the slot-3 tombstone and create a duplicate.
+        map.insert(11, 201);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&11), Some(201));
+    });
+}
+
+#[test]
+fn map_ref_try_insert_returns_existing_value() {
+    cell_gc::with_heap(|hs| {
+        let map: MapRef<i32, i32> = MapRef::new(hs);
+
+        assert!(map.try_insert(1, 10).is_ok());
+        match map.try_insert(1, 20) {
+            Err(OccupiedError(existing)) => assert_eq!(existing, 10),
+            Ok(()) => panic!("try_insert should have refused a duplicate key"),
+        }
+        assert_eq!(map.get(&1), Some(10));
+        assert_eq!(map.len(), 1);
+    });
+}
+
+#[test]
+fn bounded_vec_ref_evicts_the_opposite_end_once_full() {
+    cell_gc::with_heap(|hs| {
+        let ring: BoundedVecRef<i32> = BoundedVecRef::new(hs, 3);
+
+        ring.push_back(1);
+        ring.push_back(2);
+        ring.push_back(3);
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        // Pushing past `limit` evicts the oldest (front) element.
+        ring.push_back(4);
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        // Pushing at the front past `limit` evicts the newest (back).
+        ring.push_front(1);
+        assert_eq!(ring.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        assert_eq!(ring.pop_front(), Some(1));
+        assert_eq!(ring.pop_back(), Some(3));
+        assert_eq!(ring.iter().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(ring.len(), 1);
+    });
+}
+
+#[test]
+fn map_ref_grows_and_keeps_every_entry() {
+    cell_gc::with_heap(|hs| {
+        let map: MapRef<i32, i32> = MapRef::new(hs);
+        for i in 0..32 {
+            map.insert(i, i * 10);
+        }
+        assert_eq!(map.len(), 32);
+        for i in 0..32 {
+            assert_eq!(map.get(&i), Some(i * 10));
+        }
+    });
+}